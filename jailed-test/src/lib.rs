@@ -0,0 +1,137 @@
+//! A `#[jailed_test]` attribute macro for `jail`.
+//!
+//! FreeBSD networking tests that poke at epair interfaces, the routing table
+//! or `pf` race on the global network stack. This macro wraps an ordinary
+//! `#[test]` function so its body runs inside an ephemeral VNET jail, giving
+//! each test a private network stack that is torn down afterwards no matter
+//! how the test exits.
+//!
+//! Because `jail_attach`(2) is irreversible for the calling process, the
+//! generated harness `fork`(2)s a child and only the child attaches to the
+//! jail and runs the test body; the parent test runner stays un-jailed,
+//! `waitpid`(2)s the child and maps its exit status back to pass/panic. The
+//! jail is killed from a cleanup guard in the parent, so it is removed even if
+//! the child segfaults or the body panics.
+//!
+//! # Examples
+//!
+//! ```ignore
+//! use jailed_test::jailed_test;
+//!
+//! #[jailed_test]
+//! fn epair_is_isolated() {
+//!     // ... runs inside a fresh VNET jail ...
+//! }
+//! ```
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{parse_macro_input, ItemFn};
+
+/// Run the annotated test inside a throwaway VNET jail.
+///
+/// The attribute expands to a regular `#[test]` function that starts a uniquely
+/// named VNET jail, forks, attaches the child to the jail via
+/// [`RunningJail::attach`], runs the original body in the child and reaps it in
+/// the parent. The jail is always killed before the test returns.
+///
+/// # Required downstream dependencies
+///
+/// The expansion references `::jail` and `::libc`, so the *consuming* crate
+/// must depend on both `jail` and `libc` (in addition to `jailed-test`) for an
+/// annotated test to compile.
+///
+/// # Caveat: fork in a multithreaded runner
+///
+/// The generated harness `fork`(2)s inside the test runner, which is
+/// multithreaded, and then runs non-async-signal-safe code in the child —
+/// `attach().expect(...)` allocates and formats, and the test body itself is
+/// arbitrary. This is inherent to the requested fork-and-confine design (only
+/// the child may perform the irreversible `jail_attach`); keep that in mind if
+/// a test body touches locks or allocators that a concurrent runner thread
+/// might have held at fork time.
+#[proc_macro_attribute]
+pub fn jailed_test(_attr: TokenStream, item: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(item as ItemFn);
+
+    let attrs = &input.attrs;
+    let vis = &input.vis;
+    let sig = &input.sig;
+    let block = &input.block;
+    let name = &sig.ident;
+
+    let expanded = quote! {
+        #[test]
+        #(#attrs)*
+        #vis fn #name() {
+            // The original test body, kept as a nested item so it can run
+            // unchanged inside the forked child.
+            fn __jailed_test_body() #block
+
+            // Jail names must be unique across concurrently running tests; the
+            // test name plus the runner's pid is sufficient within one binary.
+            let __jailed_test_name = format!(
+                "jailedtest_{}_{}",
+                stringify!(#name),
+                ::std::process::id()
+            );
+
+            let __jail = ::jail::StoppedJail::new("/rescue")
+                .name(&__jailed_test_name)
+                .param("vnet", ::jail::param::Value::Int(1))
+                .start()
+                .expect("could not start VNET jail for test");
+
+            // `RunningJail` is `Copy`; the guard keeps its own copy so the jail
+            // is killed even if the fork, the wait or the body panics.
+            struct __JailGuard(::jail::RunningJail);
+            impl ::std::ops::Drop for __JailGuard {
+                fn drop(&mut self) {
+                    let _ = self.0.kill();
+                }
+            }
+            let __guard = __JailGuard(__jail);
+
+            let __pid = unsafe { ::libc::fork() };
+            if __pid < 0 {
+                panic!("fork() failed: {}", ::std::io::Error::last_os_error());
+            } else if __pid == 0 {
+                // Child: attach to the jail, then run the body. The attach and
+                // the body are both run inside `catch_unwind` so the child can
+                // never unwind past this function — otherwise libtest's own
+                // wrapper would catch the panic and the child would carry on as
+                // a second test runner. Any panic is mapped to exit code 101
+                // (the status the default panic hook would produce); the hook
+                // still writes the message to stderr first.
+                let __jail = __guard.0;
+                let __result = ::std::panic::catch_unwind(move || {
+                    __jail.attach().expect("could not attach to test jail");
+                    __jailed_test_body();
+                });
+                unsafe { ::libc::_exit(if __result.is_ok() { 0 } else { 101 }) };
+            }
+
+            // Parent: reap the child and translate its status into a verdict.
+            let mut __status: ::libc::c_int = 0;
+            let __waited = unsafe { ::libc::waitpid(__pid, &mut __status, 0) };
+            if __waited < 0 {
+                panic!("waitpid() failed: {}", ::std::io::Error::last_os_error());
+            }
+
+            drop(__guard);
+
+            if ::libc::WIFSIGNALED(__status) {
+                panic!(
+                    "jailed test child killed by signal {}",
+                    ::libc::WTERMSIG(__status)
+                );
+            }
+            if !::libc::WIFEXITED(__status) || ::libc::WEXITSTATUS(__status) != 0 {
+                panic!("jailed test failed in child");
+            }
+        }
+    };
+
+    expanded.into()
+}
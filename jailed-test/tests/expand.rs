@@ -0,0 +1,10 @@
+//! Compile-pass coverage for the `#[jailed_test]` expansion.
+//!
+//! The generated harness pulls in `jail` and `libc`, which are only available
+//! on FreeBSD, so the fixtures are only exercised there.
+#[cfg(target_os = "freebsd")]
+#[test]
+fn expansion_compiles() {
+    let t = trybuild::TestCases::new();
+    t.pass("tests/pass/basic.rs");
+}
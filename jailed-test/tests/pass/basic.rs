@@ -0,0 +1,10 @@
+use jailed_test::jailed_test;
+
+// A plain test body must expand to something that type-checks: a `#[test]`
+// function wrapping the fork/attach/reap harness.
+#[jailed_test]
+fn runs_inside_a_jail() {
+    assert_eq!(2 + 2, 4);
+}
+
+fn main() {}
@@ -1,6 +1,9 @@
 //! Jail-Specific extensions to the `std::process` module
-use crate::{JailError, RunningJail};
+use crate::{JailError, RunningJail, StoppedJail};
 use log::trace;
+use std::io;
+use std::process::Output;
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd, RawFd};
 use std::os::unix::process::CommandExt;
 use std::process::Command as StdCommand;
 #[cfg(feature = "tokio")]
@@ -76,3 +79,481 @@ impl Jailed for TokioCommand {
         self
     }
 }
+
+/// Extension to control file-descriptor plumbing into a jailed child.
+///
+/// [`Jailed::jail`] leaves fd inheritance to whatever `Command` does by
+/// default. This hands a jailed child exactly the descriptors the caller
+/// wants — e.g. a pre-opened listening socket for a daemon — and closes
+/// every other descriptor above stderr on exec. stdin, stdout and stderr are
+/// left open.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use std::process::Command;
+/// # use std::os::unix::io::AsRawFd;
+/// use jail::process::JailedKeepFds;
+///
+/// # let jail = jail::StoppedJail::new("/rescue")
+/// #     .name("testjail_keepfds")
+/// #     .start()
+/// #     .expect("could not start jail");
+/// # let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+/// #
+/// // Hand the pre-opened listening socket to the child as fd 3.
+/// let output = Command::new("/usr/sbin/daemon")
+///     .jail_keep_fds(&jail, &[(listener.as_raw_fd(), 3)])
+///     .output()
+///     .expect("Failed to execute command");
+///
+/// println!("output: {:?}", output.stdout);
+/// # jail.kill().expect("could not stop jail");
+/// ```
+#[cfg(target_os = "freebsd")]
+pub trait JailedKeepFds {
+    /// Remap and preserve specific descriptors across the jailed `exec`.
+    ///
+    /// In the child, before the attach, the `pre_exec` hook `dup2`'s each
+    /// `src_fd` onto its `dst_fd`, clears `FD_CLOEXEC` on the kept
+    /// destinations and sets `FD_CLOEXEC` on every other inherited descriptor
+    /// above stderr, so apart from stdin/stdout/stderr only the requested fds
+    /// leak into the jail. The attach runs last, so
+    /// a failed attach still aborts the spawn. `maps` is a slice of
+    /// `(src_fd, dst_fd)` pairs.
+    fn jail_keep_fds(&mut self, jail: &RunningJail, maps: &[(RawFd, RawFd)]) -> &mut Self;
+}
+
+#[cfg(target_os = "freebsd")]
+fn keep_fds_then_attach(jail: RunningJail, maps: &[(RawFd, RawFd)]) -> io::Result<()> {
+    trace!("pre_exec handler: remapping fds, then attaching");
+
+    // Mark every inherited descriptor close-on-exec, then clear the flag on
+    // the ones we deliberately keep, so nothing else leaks into the jail.
+    // Leave stdin/stdout/stderr alone so the child keeps its standard streams;
+    // callers wanting to replace those pass them explicitly in `maps`.
+    let max = unsafe { libc::sysconf(libc::_SC_OPEN_MAX) };
+    let max = if max < 0 { 1024 } else { max as RawFd };
+    for fd in (libc::STDERR_FILENO + 1)..max {
+        let flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+        if flags < 0 {
+            // Not an open descriptor; skip it.
+            continue;
+        }
+        if unsafe { libc::fcntl(fd, libc::F_SETFD, flags | libc::FD_CLOEXEC) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    for &(src, dst) in maps {
+        if unsafe { libc::dup2(src, dst) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let flags = unsafe { libc::fcntl(dst, libc::F_GETFD) };
+        if flags < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if unsafe { libc::fcntl(dst, libc::F_SETFD, flags & !libc::FD_CLOEXEC) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    jail.attach().map_err(|err| match err {
+        JailError::JailAttachError(e) => e,
+        _ => panic!("jail.attach() failed with unexpected error"),
+    })
+}
+
+#[cfg(target_os = "freebsd")]
+impl JailedKeepFds for StdCommand {
+    fn jail_keep_fds(&mut self, jail: &RunningJail, maps: &[(RawFd, RawFd)]) -> &mut Self {
+        trace!(
+            "std::process::Command::jail_keep_fds({:?}, jail={:?}, maps={:?})",
+            self,
+            jail,
+            maps
+        );
+        let jail = *jail;
+        let maps = maps.to_vec();
+        unsafe {
+            self.pre_exec(move || keep_fds_then_attach(jail, &maps));
+        }
+
+        self
+    }
+}
+
+#[cfg(all(target_os = "freebsd", feature = "tokio"))]
+impl JailedKeepFds for TokioCommand {
+    fn jail_keep_fds(&mut self, jail: &RunningJail, maps: &[(RawFd, RawFd)]) -> &mut Self {
+        trace!(
+            "tokio::process::Command::jail_keep_fds({:?}, jail={:?}, maps={:?})",
+            self,
+            jail,
+            maps
+        );
+        let jail = *jail;
+        let maps = maps.to_vec();
+        unsafe {
+            self.pre_exec(move || keep_fds_then_attach(jail, &maps));
+        }
+
+        self
+    }
+}
+
+/// Extension to drop privileges to a jail-local user after attaching.
+///
+/// [`Jailed::jail`] attaches as whatever uid the parent runs as (usually
+/// root). This resolves a username against the jail's own passwd database and
+/// switches to it, so a jailed service can run unprivileged.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use std::process::Command;
+/// use jail::process::JailedUser;
+///
+/// # let jail = jail::StoppedJail::new("/rescue")
+/// #     .name("testjail_user")
+/// #     .start()
+/// #     .expect("could not start jail");
+/// #
+/// let output = Command::new("/usr/bin/id")
+///     .jail_user(&jail, "nobody")
+///     .output()
+///     .expect("Failed to execute command");
+///
+/// println!("output: {:?}", output.stdout);
+/// # jail.kill().expect("could not stop jail");
+/// ```
+#[cfg(target_os = "freebsd")]
+pub trait JailedUser {
+    /// Attach to `jail` and then drop privileges to `user`.
+    ///
+    /// In the child, the `pre_exec` hook performs `jail_attach`(2) first and
+    /// only then resolves `user` with `getpwnam_r`(3) — so the lookup reads
+    /// the jail's `/etc/passwd`, not the host's — followed by `initgroups`(3)
+    /// (which installs the supplementary groups with `setgroups`), `setgid`
+    /// and `setuid` to that user. Any failure in the lookup or the
+    /// setuid sequence fails the spawn.
+    fn jail_user(&mut self, jail: &RunningJail, user: &str) -> &mut Self;
+}
+
+#[cfg(target_os = "freebsd")]
+fn switch_to_jail_user(jail: RunningJail, user: &std::ffi::CStr) -> io::Result<()> {
+    trace!("pre_exec handler: attaching, then dropping to jail user");
+    jail.attach().map_err(|err| match err {
+        JailError::JailAttachError(e) => e,
+        _ => panic!("jail.attach() failed with unexpected error"),
+    })?;
+
+    // Resolve the user against the now-jailed root filesystem's passwd db.
+    // Size the scratch buffer from sysconf, and grow it on `ERANGE` so users
+    // with large passwd entries don't fail the spawn.
+    let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut result: *mut libc::passwd = std::ptr::null_mut();
+    let mut cap = match unsafe { libc::sysconf(libc::_SC_GETPW_R_SIZE_MAX) } {
+        n if n > 0 => n as usize,
+        _ => 1024,
+    };
+    let mut buf: Vec<libc::c_char> = vec![0; cap];
+    loop {
+        let ret = unsafe {
+            libc::getpwnam_r(
+                user.as_ptr(),
+                &mut pwd,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        };
+        if ret == 0 {
+            break;
+        }
+        if ret == libc::ERANGE {
+            cap *= 2;
+            buf.resize(cap, 0);
+            continue;
+        }
+        return Err(io::Error::from_raw_os_error(ret));
+    }
+    if result.is_null() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "no such user in jail passwd database",
+        ));
+    }
+
+    // Initialise the supplementary groups, then give up gid and uid. The
+    // order matters: setgid/setuid must follow initgroups while still
+    // privileged. `initgroups` is used in place of a bare `setgroups`: it
+    // reads the jail's `/etc/group` and installs the full supplementary set.
+    if unsafe { libc::initgroups(user.as_ptr(), pwd.pw_gid) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if unsafe { libc::setgid(pwd.pw_gid) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if unsafe { libc::setuid(pwd.pw_uid) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+#[cfg(target_os = "freebsd")]
+impl JailedUser for StdCommand {
+    fn jail_user(&mut self, jail: &RunningJail, user: &str) -> &mut Self {
+        trace!(
+            "std::process::Command::jail_user({:?}, jail={:?}, user={:?})",
+            self,
+            jail,
+            user
+        );
+        let jail = *jail;
+        let user = std::ffi::CString::new(user).expect("user name contained a nul byte");
+        unsafe {
+            self.pre_exec(move || switch_to_jail_user(jail, &user));
+        }
+
+        self
+    }
+}
+
+#[cfg(all(target_os = "freebsd", feature = "tokio"))]
+impl JailedUser for TokioCommand {
+    fn jail_user(&mut self, jail: &RunningJail, user: &str) -> &mut Self {
+        trace!(
+            "tokio::process::Command::jail_user({:?}, jail={:?}, user={:?})",
+            self,
+            jail,
+            user
+        );
+        let jail = *jail;
+        let user = std::ffi::CString::new(user).expect("user name contained a nul byte");
+        unsafe {
+            self.pre_exec(move || switch_to_jail_user(jail, &user));
+        }
+
+        self
+    }
+}
+
+/// Owning handle to the master side of a pseudo-terminal allocated for a
+/// jailed command.
+///
+/// The master stays with the caller, who reads and writes it to drive the
+/// session; the slave is wired up as the child's controlling terminal by
+/// [`JailedPty::jail_pty`]. Dropping this closes the master fd.
+#[cfg(target_os = "freebsd")]
+#[derive(Debug)]
+pub struct PtyMaster(OwnedFd);
+
+#[cfg(target_os = "freebsd")]
+impl AsRawFd for PtyMaster {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+#[cfg(target_os = "freebsd")]
+impl IntoRawFd for PtyMaster {
+    fn into_raw_fd(self) -> RawFd {
+        self.0.into_raw_fd()
+    }
+}
+
+/// Extension to run a jailed command on its own controlling pseudo-terminal.
+///
+/// Unlike [`Jailed::jail`], which only wires up `jail_attach`(2), this gives
+/// the child a controlling terminal so interactive programs (a shell, `login`,
+/// a TUI installer) can be driven from the returned master fd.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use std::process::Command;
+/// use jail::process::JailedPty;
+///
+/// # let jail = jail::StoppedJail::new("/rescue")
+/// #     .name("testjail_pty")
+/// #     .start()
+/// #     .expect("could not start jail");
+/// #
+/// let (mut child, _master) = Command::new("/bin/sh")
+///     .jail_pty(&jail)
+///     .expect("could not spawn jailed shell");
+///
+/// child.wait().expect("wait failed");
+/// # jail.kill().expect("could not stop jail");
+/// ```
+#[cfg(target_os = "freebsd")]
+pub trait JailedPty {
+    /// The spawned child handle type (`std::process::Child` or the tokio
+    /// equivalent).
+    type Child;
+
+    /// Allocate a pseudo-terminal, spawn the command inside `jail` with the
+    /// slave as its controlling terminal, and return the child and the master.
+    ///
+    /// In the child, before attaching, the `pre_exec` hook calls `setsid`(2),
+    /// `dup2`'s the slave onto stdin/stdout/stderr and issues
+    /// `ioctl(TIOCSCTTY)` to acquire it as the controlling terminal, and only
+    /// then performs `jail_attach`(2). Establishing the session first means a
+    /// failed attach still aborts the spawn cleanly.
+    ///
+    /// The command is spawned internally so the parent's copy of the slave can
+    /// be closed afterwards; otherwise reads on the returned [`PtyMaster`]
+    /// would never see EOF once the jailed child exits. The `PtyMaster` is
+    /// used by the caller to read and write the session.
+    fn jail_pty(&mut self, jail: &RunningJail) -> io::Result<(Self::Child, PtyMaster)>;
+}
+
+/// Allocate a pseudo-terminal, returning both ends as owned fds.
+///
+/// The master is kept by the caller (closed on drop); the slave is handed to
+/// the `pre_exec` hook as a raw fd and closed by the parent once the child has
+/// been spawned.
+#[cfg(target_os = "freebsd")]
+fn allocate_pty() -> io::Result<(OwnedFd, OwnedFd)> {
+    let mut master: RawFd = -1;
+    let mut slave: RawFd = -1;
+    // SAFETY: openpty writes the two fds through the out-pointers; the
+    // remaining arguments are optional and left null.
+    let ret = unsafe {
+        libc::openpty(
+            &mut master,
+            &mut slave,
+            std::ptr::null_mut(),
+            std::ptr::null(),
+            std::ptr::null(),
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // Take ownership of both ends up front so they are closed if anything
+    // later fails.
+    Ok((unsafe { OwnedFd::from_raw_fd(master) }, unsafe {
+        OwnedFd::from_raw_fd(slave)
+    }))
+}
+
+/// `pre_exec` body for [`JailedPty::jail_pty`]: establish the controlling
+/// terminal from `slave`, drop both pty fds from the child, then attach.
+#[cfg(target_os = "freebsd")]
+fn setup_pty_then_attach(jail: RunningJail, slave: RawFd, master: RawFd) -> io::Result<()> {
+    trace!("pre_exec handler: allocating controlling tty, then attaching");
+    if unsafe { libc::setsid() } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    for fd in &[libc::STDIN_FILENO, libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+        if unsafe { libc::dup2(slave, *fd) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    if unsafe { libc::ioctl(slave, libc::TIOCSCTTY, 0) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // The child keeps only the dup'd stdio; close the host-side master and the
+    // now-redundant slave so neither pty fd leaks across the exec.
+    if slave > libc::STDERR_FILENO {
+        unsafe { libc::close(slave) };
+    }
+    unsafe { libc::close(master) };
+    jail.attach().map_err(|err| match err {
+        JailError::JailAttachError(e) => e,
+        _ => panic!("jail.attach() failed with unexpected error"),
+    })
+}
+
+#[cfg(target_os = "freebsd")]
+impl JailedPty for StdCommand {
+    type Child = std::process::Child;
+
+    fn jail_pty(&mut self, jail: &RunningJail) -> io::Result<(Self::Child, PtyMaster)> {
+        trace!("std::process::Command::jail_pty({:?}, jail={:?})", self, jail);
+        let jail = *jail;
+        let (master, slave) = allocate_pty()?;
+        let master_fd = master.as_raw_fd();
+        let slave_fd = slave.as_raw_fd();
+        unsafe {
+            self.pre_exec(move || setup_pty_then_attach(jail, slave_fd, master_fd));
+        }
+
+        let child = self.spawn()?;
+        // Close the parent's slave so reads on the master see EOF once the
+        // jailed child exits and closes its stdio.
+        drop(slave);
+
+        Ok((child, PtyMaster(master)))
+    }
+}
+
+#[cfg(all(target_os = "freebsd", feature = "tokio"))]
+impl JailedPty for TokioCommand {
+    type Child = tokio::process::Child;
+
+    fn jail_pty(&mut self, jail: &RunningJail) -> io::Result<(Self::Child, PtyMaster)> {
+        trace!("tokio::process::Command::jail_pty({:?}, jail={:?})", self, jail);
+        let jail = *jail;
+        let (master, slave) = allocate_pty()?;
+        let master_fd = master.as_raw_fd();
+        let slave_fd = slave.as_raw_fd();
+        unsafe {
+            self.pre_exec(move || setup_pty_then_attach(jail, slave_fd, master_fd));
+        }
+
+        let child = self.spawn()?;
+        // Close the parent's slave so reads on the master see EOF once the
+        // jailed child exits and closes its stdio.
+        drop(slave);
+
+        Ok((child, PtyMaster(master)))
+    }
+}
+
+/// One-shot helpers for confining a single command in a throwaway jail.
+#[cfg(target_os = "freebsd")]
+impl StoppedJail {
+    /// Start the jail, run `cmd` inside it to completion and kill the jail.
+    ///
+    /// This packages the common "spin up an isolated jail purely to confine
+    /// one child" pattern: the jail is started, the [`Jailed`] attach is
+    /// applied to `cmd`, the command is run with its [`Output`] captured, and
+    /// the jail is killed before returning — even if the command fails to
+    /// spawn. The command's own exit status is reported through the returned
+    /// `Output`, not as an error. An error from starting the jail is returned
+    /// as is. Any I/O error from spawning or waiting for the child — the
+    /// attach hook aborting the spawn, but equally a failed `exec` or a failed
+    /// wait — is returned wrapped in [`JailError::JailAttachError`].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use std::process::Command;
+    /// let output = jail::StoppedJail::new("/rescue")
+    ///     .name("testjail_run_once")
+    ///     .run_once(Command::new("/hostname").arg("-s"))
+    ///     .expect("could not run command in jail");
+    ///
+    /// println!("output: {:?}", output.stdout);
+    /// ```
+    pub fn run_once(self, cmd: &mut StdCommand) -> Result<Output, JailError> {
+        trace!("StoppedJail::run_once({:?}, cmd={:?})", self, cmd);
+        let running = self.start()?;
+        let output = cmd.jail(&running).output();
+
+        // Tear the jail down regardless of how the command fared, but never let
+        // a teardown failure discard a captured result — prefer the command
+        // outcome over the kill error.
+        let _ = running.kill();
+
+        // Every spawn or wait error (attach hook, exec or wait failure) is
+        // wrapped as an attach error; the command's own non-zero exit is
+        // carried in `Output`, not an error.
+        output.map_err(JailError::JailAttachError)
+    }
+}